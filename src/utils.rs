@@ -1,4 +1,5 @@
 use anyhow::Result;
+use jsonschema::JSONSchema;
 use schemars::{schema_for, JsonSchema};
 use serde::de::DeserializeOwned;
 use serde_json::Value;
@@ -35,14 +36,149 @@ pub(crate) fn get_tokenizer<T: LLMModel>(model: &T) -> anyhow::Result<CoreBPE> {
     }
 }
 
-/// LLMs have a tendency to wrap response Json in ```json{}```. This function sanitizes
+// Strips a fenced code block or surrounding commentary from around a model's JSON response,
+// falling back to the original text unchanged if no JSON candidate is found
 pub(crate) fn remove_json_wrapper(json_response: &str) -> String {
-    let text_no_json = json_response.replace("json\n", "");
-    text_no_json.replace("```", "")
+    if let Some(fenced) = extract_fenced_json(json_response) {
+        return fenced;
+    }
+
+    extract_balanced_json(json_response).unwrap_or_else(|| json_response.to_string())
+}
+
+// Scans for Markdown fences and returns the body of the first one that parses as valid JSON
+fn extract_fenced_json(text: &str) -> Option<String> {
+    let mut cursor = 0;
+
+    while cursor < text.len() {
+        let (fence_start, fence) = [("```", 3usize), ("~~~", 3usize)]
+            .into_iter()
+            .filter_map(|(fence, _)| text[cursor..].find(fence).map(|idx| (cursor + idx, fence)))
+            .min_by_key(|(idx, _)| *idx)?;
+
+        let after_fence = fence_start + fence.len();
+        // Skip an optional language tag (e.g. `json`) up to the end of that line
+        let body_start = text[after_fence..]
+            .find('\n')
+            .map(|idx| after_fence + idx + 1)
+            .unwrap_or(after_fence);
+
+        let Some(end_offset) = text[body_start..].find(fence) else {
+            return None;
+        };
+        let body_end = body_start + end_offset;
+        let body = text[body_start..body_end].trim();
+
+        if serde_json::from_str::<Value>(body).is_ok() {
+            return Some(body.to_string());
+        }
+
+        cursor = body_end + fence.len();
+    }
+
+    None
+}
+
+// Scans the whole text for top-level `{...}`/`[...]` spans that parse as valid JSON, and
+// returns the first one not immediately preceded by illustrative phrasing (e.g. "format like"),
+// falling back to the first valid candidate overall if every one of them looks illustrative
+fn extract_balanced_json(text: &str) -> Option<String> {
+    let mut search_from = 0;
+    let mut first_valid = None;
+
+    while let Some(relative_start) = text[search_from..].find(['{', '[']) {
+        let start = search_from + relative_start;
+        let Some(end) = find_matching_close(text, start) else {
+            break;
+        };
+        let candidate = &text[start..end];
+
+        if serde_json::from_str::<Value>(candidate).is_ok() {
+            if !looks_illustrative(&text[..start]) {
+                return Some(candidate.to_string());
+            }
+            first_valid.get_or_insert_with(|| candidate.to_string());
+        }
+
+        search_from = end;
+    }
+
+    first_valid
+}
+
+// Checks whether text immediately preceding a JSON candidate flags it as an illustrative
+// example (e.g. "format like", "e.g.") rather than the model's actual answer
+fn looks_illustrative(preceding_text: &str) -> bool {
+    const MARKERS: [&str; 4] = ["format like", "example", "e.g.", "such as"];
+    let tail_chars: String = preceding_text.chars().rev().take(40).collect();
+    let window: String = tail_chars.chars().rev().collect::<String>().to_lowercase();
+    MARKERS.iter().any(|marker| window.contains(marker))
+}
+
+// Starting from `text[start]` (expected to be `{` or `[`), walks forward tracking nesting depth,
+// skipping over string literals (and their escape sequences) so braces/brackets inside string
+// values don't confuse the depth count, and returns the byte offset just past the matching close
+fn find_matching_close(text: &str, start: usize) -> Option<usize> {
+    let open = text[start..].chars().next()?;
+    let close = if open == '{' { '}' } else { ']' };
+
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (offset, ch) in text[start..].char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        if ch == '"' {
+            in_string = true;
+        } else if ch == open {
+            depth += 1;
+        } else if ch == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(start + offset + ch.len_utf8());
+            }
+        }
+    }
+
+    None
 }
 
 // This function generates a Json schema for the provided type
 pub(crate) fn get_type_schema<T: JsonSchema + DeserializeOwned>() -> Result<String> {
+    get_type_schema_with_profile::<T>(SchemaProfile::default())
+}
+
+// Targets a generated schema at a specific consumer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaProfile {
+    /// No provider-specific transform is applied.
+    Generic,
+    /// OpenAI "strict" structured outputs: `additionalProperties: false` plus all properties required.
+    OpenAiStrict,
+    /// OpenAPI-style consumers: `Option<T>` unions are rewritten as `"nullable": true`.
+    Nullable,
+}
+
+impl Default for SchemaProfile {
+    fn default() -> Self {
+        SchemaProfile::Generic
+    }
+}
+
+// This function generates a Json schema for the provided type, shaped for a specific consumer
+pub(crate) fn get_type_schema_with_profile<T: JsonSchema + DeserializeOwned>(
+    profile: SchemaProfile,
+) -> Result<String> {
     // Instruct the Assistant to answer with the right Json format
     // Output schema is extracted from the type parameter
     let mut schema = schema_for!(T);
@@ -59,27 +195,392 @@ pub(crate) fn get_type_schema<T: JsonSchema + DeserializeOwned>() -> Result<Stri
         obj.remove("title");
     }
 
+    // Apply the provider-specific transform pass, if any
+    match profile {
+        SchemaProfile::Generic => {}
+        SchemaProfile::OpenAiStrict => apply_openai_strict_profile(&mut schema_json),
+        SchemaProfile::Nullable => apply_nullable_profile(&mut schema_json),
+    }
+
     // Convert the modified JSON value back to a pretty-printed JSON string
     Ok(serde_json::to_string_pretty(&schema_json)?)
 }
 
-// The Schemars crate uses `Bool(true)` for `Value`, which essentially means "accept anything". We need to replace it with actual `Object` type
+// Recursively forces every object schema (properties map, at any depth, including definitions)
+// to disallow additional properties and to require all of its own properties
+fn apply_openai_strict_profile(value: &mut Value) {
+    if let Value::Object(obj) = value {
+        if let Some(Value::Object(properties)) = obj.get("properties") {
+            let keys: Vec<Value> = properties.keys().cloned().map(Value::String).collect();
+            obj.insert("additionalProperties".to_string(), Value::Bool(false));
+            obj.insert("required".to_string(), Value::Array(keys));
+        }
+        for nested in obj.values_mut() {
+            apply_openai_strict_profile(nested);
+        }
+    } else if let Value::Array(items) = value {
+        for item in items {
+            apply_openai_strict_profile(item);
+        }
+    }
+}
+
+// Recursively rewrites `Option<T>` unions into the `nullable: true` convention, at any depth
+fn apply_nullable_profile(value: &mut Value) {
+    if let Value::Object(obj) = value {
+        // schemars emits `Option<T>` as a `["T", "null"]` type array
+        if let Some(Value::Array(types)) = obj.get("type").cloned() {
+            if types.iter().any(|t| t.as_str() == Some("null")) {
+                let mut non_null: Vec<Value> =
+                    types.into_iter().filter(|t| t.as_str() != Some("null")).collect();
+                obj.insert(
+                    "type".to_string(),
+                    if non_null.len() == 1 {
+                        non_null.remove(0)
+                    } else {
+                        Value::Array(non_null)
+                    },
+                );
+                obj.insert("nullable".to_string(), Value::Bool(true));
+            }
+        }
+
+        // ...or as a two-variant `anyOf` with a `{"type": "null"}` branch — schemars emits this
+        // for `Option<SomeStruct>`, where the non-null branch is a bare `{"$ref": ...}`
+        if let Some(Value::Array(variants)) = obj.get("anyOf").cloned() {
+            if variants.len() == 2 {
+                let is_null = |v: &Value| v.get("type").and_then(Value::as_str) == Some("null");
+                if let Some(non_null) = variants.iter().find(|v| !is_null(v)) {
+                    if variants.iter().any(is_null) {
+                        let non_null = non_null.clone();
+                        obj.remove("anyOf");
+                        if non_null.get("$ref").is_some() {
+                            // OpenAPI ignores keywords sibling to `$ref`, so wrap it in `allOf`
+                            // instead of attaching `nullable` directly
+                            obj.insert("allOf".to_string(), Value::Array(vec![non_null]));
+                        } else if let Value::Object(non_null_obj) = non_null {
+                            obj.extend(non_null_obj);
+                        }
+                        obj.insert("nullable".to_string(), Value::Bool(true));
+                    }
+                }
+            }
+        }
+
+        for nested in obj.values_mut() {
+            apply_nullable_profile(nested);
+        }
+    } else if let Value::Array(items) = value {
+        for item in items {
+            apply_nullable_profile(item);
+        }
+    }
+}
+
+// The Schemars crate uses `Bool(true)` for `Value`, which essentially means "accept anything". We
+// need to replace it with actual `Object` type everywhere it can appear: top-level properties,
+// nested objects, array items, `all_of`/`any_of`/`one_of` subschemas, and `definitions`. `$ref`
+// pointers are never followed, so self-referential definitions can't cause infinite recursion.
 fn fix_value_schema(schema: &mut schemars::schema::RootSchema) {
-    if let Some(object) = &mut schema.schema.object {
-        // Iterate over mutable values in the `properties` BTreeMap
-        for subschema in object.properties.values_mut() {
-            // Check if the schema is `Bool(true)` (placeholder for `serde_json::Value`)
-            if let schemars::schema::Schema::Bool(true) = subschema {
-                // Replace `true` with a proper schema for `serde_json::Value`
-                *subschema = schemars::schema::Schema::Object(schemars::schema::SchemaObject {
-                    instance_type: Some(schemars::schema::InstanceType::Object.into()),
-                    ..Default::default()
-                });
+    for definition in schema.definitions.values_mut() {
+        fix_value_placeholder(definition);
+    }
+    fix_value_placeholders_in_object(&mut schema.schema);
+}
+
+// Replaces `schema` in place with the proper `Object` schema if it's the `Bool(true)` `Value`
+// placeholder; if it's already a `SchemaObject`, recurses into it instead
+fn fix_value_placeholder(schema: &mut schemars::schema::Schema) {
+    use schemars::schema::{InstanceType, Schema, SchemaObject};
+
+    if let Schema::Bool(true) = schema {
+        *schema = Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        });
+    } else if let Schema::Object(object) = schema {
+        fix_value_placeholders_in_object(object);
+    }
+}
+
+// Walks every place a subschema can hide inside a `SchemaObject`
+fn fix_value_placeholders_in_object(object: &mut schemars::schema::SchemaObject) {
+    use schemars::schema::SingleOrVec;
+
+    if let Some(object_validation) = &mut object.object {
+        for subschema in object_validation.properties.values_mut() {
+            fix_value_placeholder(subschema);
+        }
+        if let Some(additional_properties) = &mut object_validation.additional_properties {
+            fix_value_placeholder(additional_properties);
+        }
+    }
+
+    if let Some(array_validation) = &mut object.array {
+        match &mut array_validation.items {
+            Some(SingleOrVec::Single(item)) => fix_value_placeholder(item),
+            Some(SingleOrVec::Vec(items)) => {
+                for item in items {
+                    fix_value_placeholder(item);
+                }
+            }
+            None => {}
+        }
+    }
+
+    if let Some(subschemas) = &mut object.subschemas {
+        for list in [
+            &mut subschemas.all_of,
+            &mut subschemas.any_of,
+            &mut subschemas.one_of,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            for item in list {
+                fix_value_placeholder(item);
             }
         }
     }
 }
 
+/// A single JSON Schema validation failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// JSON pointer to the value that failed validation (e.g. `/items/0/name`)
+    pub path: String,
+    /// The schema keyword that was violated (e.g. `required`, `type`, `enum`)
+    pub keyword: String,
+    /// Human-readable description of the violation
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.path, self.keyword, self.message)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+// Compiles a schema value (as produced by `get_type_schema`) into a reusable validation tree.
+// Takes the already-parsed `Value` rather than owning it, so a caller that needs to validate
+// more than once (e.g. a retry loop) compiles it exactly once and reuses the result.
+fn compile_schema(schema_value: &Value) -> Result<JSONSchema> {
+    JSONSchema::options()
+        .with_draft(jsonschema::Draft::Draft7)
+        .compile(schema_value)
+        .map_err(|error| anyhow::anyhow!("Failed to compile JSON schema: {error}"))
+}
+
+// Runs a compiled schema against `value`, turning jsonschema-rs's errors into `SchemaError`s
+fn validate_against(
+    compiled: &JSONSchema,
+    value: &Value,
+) -> std::result::Result<(), Vec<SchemaError>> {
+    compiled.validate(value).map_err(|errors| {
+        errors
+            .map(|error| SchemaError {
+                path: error.instance_path.to_string(),
+                keyword: schema_error_keyword(&error.kind).to_string(),
+                message: error.to_string(),
+            })
+            .collect()
+    })
+}
+
+// Maps a jsonschema-rs error kind to the schema keyword it corresponds to; kinds not listed
+// here map to `validation`
+fn schema_error_keyword(kind: &jsonschema::error::ValidationErrorKind) -> &'static str {
+    use jsonschema::error::ValidationErrorKind;
+
+    match kind {
+        ValidationErrorKind::Required { .. } => "required",
+        ValidationErrorKind::Type { .. } => "type",
+        ValidationErrorKind::Enum { .. } => "enum",
+        ValidationErrorKind::MinProperties { .. } => "minProperties",
+        ValidationErrorKind::MaxProperties { .. } => "maxProperties",
+        ValidationErrorKind::MinItems { .. } => "minItems",
+        ValidationErrorKind::MaxItems { .. } => "maxItems",
+        ValidationErrorKind::MinLength { .. } => "minLength",
+        ValidationErrorKind::MaxLength { .. } => "maxLength",
+        ValidationErrorKind::AdditionalProperties { .. } => "additionalProperties",
+        ValidationErrorKind::OneOfMultipleValid | ValidationErrorKind::OneOfNotValid => "oneOf",
+        ValidationErrorKind::AnyOf => "anyOf",
+        _ => "validation",
+    }
+}
+
+// Validates a parsed response `Value` against the JSON Schema generated for `T`, collecting
+// every violation instead of stopping at the first one
+pub(crate) fn validate_response<T: JsonSchema + DeserializeOwned>(
+    value: &Value,
+) -> std::result::Result<(), Vec<SchemaError>> {
+    let schema_error = |error: anyhow::Error| {
+        vec![SchemaError {
+            path: "/".to_string(),
+            keyword: "schema".to_string(),
+            message: error.to_string(),
+        }]
+    };
+
+    let schema_json = get_type_schema::<T>().map_err(schema_error)?;
+    let schema_value: Value = serde_json::from_str(&schema_json)
+        .map_err(|error| schema_error(error.into()))?;
+    let compiled = compile_schema(&schema_value).map_err(schema_error)?;
+
+    validate_against(&compiled, value)
+}
+
+// Wraps an arbitrary error (JSON parse failure, final deserialize failure) as the single-element
+// `SchemaError` list shape the retry loop below uses for every other kind of failure
+fn error_as_schema_errors(keyword: &str, error: impl std::fmt::Display) -> Vec<SchemaError> {
+    vec![SchemaError {
+        path: "/".to_string(),
+        keyword: keyword.to_string(),
+        message: error.to_string(),
+    }]
+}
+
+// Parses `response` into `T`, enforcing schema conformance and driving a bounded automatic
+// repair loop: on any failure (malformed JSON, schema validation, or a final deserialize
+// mismatch), `on_invalid` is handed the failed response text plus the collected `SchemaError`s
+// and must return a fresh response string to try again, up to `max_retries`. The schema is
+// compiled once up front and reused across every attempt. When `fill_defaults` is set,
+// `apply_schema_defaults` runs against each attempt before it's validated.
+pub(crate) fn parse_validated_response<T, F>(
+    response: &str,
+    max_retries: usize,
+    fill_defaults: bool,
+    mut on_invalid: F,
+) -> Result<T>
+where
+    T: JsonSchema + DeserializeOwned,
+    F: FnMut(&str, &[SchemaError]) -> Result<String>,
+{
+    let schema_json = get_type_schema::<T>()?;
+    let schema_value: Value = serde_json::from_str(&schema_json)?;
+    let compiled = compile_schema(&schema_value)?;
+
+    let mut current = response.to_string();
+    let mut attempt = 0;
+    loop {
+        let outcome = (|| -> std::result::Result<T, Vec<SchemaError>> {
+            let mut parsed: Value = serde_json::from_str(&current)
+                .map_err(|error| error_as_schema_errors("parse", error))?;
+            if fill_defaults {
+                apply_schema_defaults(&schema_value, &mut parsed);
+            }
+            validate_against(&compiled, &parsed)?;
+            serde_json::from_value(parsed).map_err(|error| error_as_schema_errors("parse", error))
+        })();
+
+        match outcome {
+            Ok(value) => return Ok(value),
+            Err(errors) => {
+                if attempt >= max_retries {
+                    return Err(anyhow::anyhow!(
+                        "Response still failed schema validation after {attempt} retries: {errors:?}"
+                    ));
+                }
+                current = on_invalid(&current, &errors)?;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// Fills in missing fields that the schema declares a `default` for, recursing into nested
+// objects (including `$ref`s) so defaults propagate at every level
+pub(crate) fn apply_schema_defaults(schema: &Value, response: &mut Value) {
+    apply_schema_defaults_inner(schema, schema, response);
+}
+
+fn apply_schema_defaults_inner(root_schema: &Value, schema: &Value, response: &mut Value) {
+    let schema = resolve_schema_ref(root_schema, schema);
+    let (Some(properties), Value::Object(response_obj)) =
+        (schema.get("properties").and_then(Value::as_object), response)
+    else {
+        return;
+    };
+
+    for (key, property_schema) in properties {
+        match response_obj.get_mut(key) {
+            None => {
+                if let Some(default) = property_schema.get("default") {
+                    response_obj.insert(key.clone(), default.clone());
+                }
+            }
+            Some(existing_value) => {
+                apply_schema_defaults_inner(root_schema, property_schema, existing_value);
+            }
+        }
+    }
+}
+
+// Follows a single `$ref` hop into `root_schema`'s `definitions`, if `schema` is a reference —
+// either directly, or wrapped in a single-element `allOf`/`anyOf` the way schemars emits it when
+// a field referencing another struct also carries its own doc comment or other metadata
+fn resolve_schema_ref<'a>(root_schema: &'a Value, schema: &'a Value) -> &'a Value {
+    schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .or_else(|| single_element_ref(schema, "allOf"))
+        .or_else(|| single_element_ref(schema, "anyOf"))
+        .and_then(|reference| reference.strip_prefix("#/definitions/"))
+        .and_then(|name| root_schema.get("definitions")?.get(name))
+        .unwrap_or(schema)
+}
+
+// Looks for a `$ref` inside a single-element `allOf`/`anyOf` array
+fn single_element_ref<'a>(schema: &'a Value, keyword: &str) -> Option<&'a str> {
+    match schema.get(keyword)?.as_array()?.as_slice() {
+        [only] => only.get("$ref").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+// One flattened top-level parameter for a function-calling / tool-use definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaMember {
+    pub name: String,
+    pub description: Option<String>,
+    pub schema: Value,
+    pub required: bool,
+}
+
+// Flattens the output of `get_type_schema::<T>()` into a named list of tool parameters,
+// resolving one level of `$ref` so a referenced sub-struct's schema/description flattens in too
+pub(crate) fn schema2members(schema: &Value) -> Result<Vec<SchemaMember>> {
+    let properties = schema.get("properties").and_then(Value::as_object).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Cannot flatten schema into tool parameters: expected a flat object with a 'properties' map, got {schema}"
+        )
+    })?;
+
+    let required: std::collections::HashSet<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|values| values.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    Ok(properties
+        .iter()
+        .map(|(name, property_schema)| {
+            let resolved = resolve_schema_ref(schema, property_schema);
+            SchemaMember {
+                name: name.clone(),
+                description: resolved
+                    .get("description")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                schema: resolved.clone(),
+                required: required.contains(name.as_str()),
+            }
+        })
+        .collect())
+}
+
 //Used internally to pick a number from range based on its % representation
 pub(crate) fn map_to_range(min: u32, max: u32, target: u32) -> f32 {
     // Cap the target to the percentage range [0, 100]
@@ -99,7 +600,11 @@ mod tests {
     use serde_json::Value;
 
     use crate::llm_models::OpenAIModels;
-    use crate::utils::{fix_value_schema, get_tokenizer, get_type_schema, map_to_range};
+    use crate::utils::{
+        apply_schema_defaults, fix_value_schema, get_tokenizer, get_type_schema,
+        get_type_schema_with_profile, map_to_range, parse_validated_response,
+        remove_json_wrapper, schema2members, validate_response, SchemaProfile,
+    };
 
     #[derive(JsonSchema, Serialize, Deserialize)]
     struct SimpleStruct {
@@ -118,6 +623,57 @@ mod tests {
         optional_field: Option<String>,
     }
 
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithOptionalRef {
+        maybe_info: Option<SimpleStruct>,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithVecValue {
+        items: Vec<serde_json::Value>,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithNestedValue {
+        data: serde_json::Value,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithNestedValueHolder {
+        nested: StructWithNestedValue,
+    }
+
+    fn default_name() -> String {
+        "anonymous".to_string()
+    }
+
+    // `#[schemars(default = ...)]` rather than `#[serde(default = ...)]`: the schema carries a
+    // default and drops `name` from `required`, but serde itself still treats it as mandatory,
+    // so a response missing `name` genuinely fails to deserialize unless something fills it in
+    // first -- exactly the gap `apply_schema_defaults` closes.
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithDefault {
+        id: i32,
+        #[schemars(default = "default_name")]
+        name: String,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StructWithDefaultHolder {
+        info: StructWithDefault,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct ToolParams {
+        /// The city to look up
+        city: String,
+        /// Optional unit override
+        unit: Option<String>,
+        info: SimpleStruct,
+        /// Extra info about something
+        documented_info: SimpleStruct,
+    }
+
     // Tokenizer tests
     #[test]
     fn it_computes_gpt3_5_tokenization() {
@@ -132,6 +688,88 @@ mod tests {
         );
     }
 
+    // Extracting Json out of wrapped / commentary-laden model responses
+    #[test]
+    fn test_remove_json_wrapper_handles_json_fence() {
+        let wrapped = "```json\n{\"id\": 1}\n```";
+        assert_eq!(remove_json_wrapper(wrapped), "{\"id\": 1}");
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_handles_bare_fence() {
+        let wrapped = "```\n{\"id\": 1}\n```";
+        assert_eq!(remove_json_wrapper(wrapped), "{\"id\": 1}");
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_handles_tilde_fence() {
+        let wrapped = "~~~\n{\"id\": 1}\n~~~";
+        assert_eq!(remove_json_wrapper(wrapped), "{\"id\": 1}");
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_handles_prose_around_fence() {
+        let wrapped = "Here you go:\n```json\n{\"id\": 1}\n```\nLet me know if you need anything else.";
+        assert_eq!(remove_json_wrapper(wrapped), "{\"id\": 1}");
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_picks_first_valid_fenced_block() {
+        let wrapped = "```text\nnot json\n```\nhere's the answer:\n```json\n{\"id\": 2}\n```";
+        assert_eq!(remove_json_wrapper(wrapped), "{\"id\": 2}");
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_falls_back_to_brace_matching_with_prose() {
+        let wrapped = "Sure, here's the result: {\"id\": 1, \"name\": \"test\"} Hope that helps!";
+        assert_eq!(
+            remove_json_wrapper(wrapped),
+            "{\"id\": 1, \"name\": \"test\"}"
+        );
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_brace_matching_respects_nested_braces_in_strings() {
+        let wrapped = "prefix {\"note\": \"contains a } brace\"} suffix";
+        assert_eq!(
+            remove_json_wrapper(wrapped),
+            "{\"note\": \"contains a } brace\"}"
+        );
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_handles_array_root() {
+        let wrapped = "prose before [{\"id\": 1}, {\"id\": 2}] prose after";
+        assert_eq!(
+            remove_json_wrapper(wrapped),
+            "[{\"id\": 1}, {\"id\": 2}]"
+        );
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_prefers_real_payload_over_illustrative_example() {
+        let wrapped = "Respond using a format like { \"key\": \"value\" }, here is the real data: {\"id\": 1, \"name\": \"test\"}";
+        assert_eq!(
+            remove_json_wrapper(wrapped),
+            "{\"id\": 1, \"name\": \"test\"}"
+        );
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_prefers_real_payload_preceding_illustrative_example() {
+        let wrapped = "{\"id\": 1, \"name\": \"real\"} by the way, a schema like { \"id\": 0 } is also valid";
+        assert_eq!(
+            remove_json_wrapper(wrapped),
+            "{\"id\": 1, \"name\": \"real\"}"
+        );
+    }
+
+    #[test]
+    fn test_remove_json_wrapper_returns_original_when_nothing_found() {
+        let text = "no json here at all";
+        assert_eq!(remove_json_wrapper(text), text);
+    }
+
     // Generating correct schema for types
     #[test]
     fn test_get_type_schema_simple_struct() {
@@ -381,6 +1019,384 @@ mod tests {
         assert!(schema.schema.object.is_none());
     }
 
+    #[test]
+    fn test_fix_value_schema_recurses_into_vec_value() {
+        let schema_json = get_type_schema::<StructWithVecValue>().unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let items_schema = &schema_value["properties"]["items"]["items"];
+        assert_eq!(
+            items_schema["type"].as_str(),
+            Some("object"),
+            "Expected Vec<Value>'s item schema to be patched to 'object'"
+        );
+    }
+
+    #[test]
+    fn test_fix_value_schema_recurses_into_nested_struct_field() {
+        let schema_json = get_type_schema::<StructWithNestedValueHolder>().unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let data_schema = &schema_value["definitions"]["StructWithNestedValue"]["properties"]["data"];
+        assert_eq!(
+            data_schema["type"].as_str(),
+            Some("object"),
+            "Expected Value field nested inside a referenced definition to be patched to 'object'"
+        );
+    }
+
+    // Emitting provider-specific schema profiles
+    #[test]
+    fn test_get_type_schema_with_profile_generic_matches_default() {
+        let generic = get_type_schema_with_profile::<NestedStruct>(SchemaProfile::Generic).unwrap();
+        let default = get_type_schema::<NestedStruct>().unwrap();
+
+        assert_eq!(generic, default);
+    }
+
+    #[test]
+    fn test_openai_strict_profile_forces_additional_properties_false() {
+        let schema_json = get_type_schema_with_profile::<NestedStruct>(SchemaProfile::OpenAiStrict)
+            .unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        assert_eq!(
+            schema_value["additionalProperties"].as_bool(),
+            Some(false),
+            "Expected top-level object to disallow additional properties"
+        );
+
+        let nested = &schema_value["definitions"]["SimpleStruct"];
+        assert_eq!(
+            nested["additionalProperties"].as_bool(),
+            Some(false),
+            "Expected nested definition to disallow additional properties"
+        );
+    }
+
+    #[test]
+    fn test_openai_strict_profile_requires_every_property() {
+        let schema_json = get_type_schema_with_profile::<NestedStruct>(SchemaProfile::OpenAiStrict)
+            .unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let required = schema_value["required"].as_array().unwrap();
+        // `optional_field` must be folded into `required` too, even though it's an `Option`
+        assert!(required.iter().any(|v| v == "optional_field"));
+        assert!(required.iter().any(|v| v == "info"));
+    }
+
+    #[test]
+    fn test_nullable_profile_rewrites_option_as_nullable_flag() {
+        let schema_json =
+            get_type_schema_with_profile::<NestedStruct>(SchemaProfile::Nullable).unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let optional_field_schema = &schema_value["properties"]["optional_field"];
+        assert_eq!(optional_field_schema["nullable"].as_bool(), Some(true));
+        assert_eq!(optional_field_schema["type"].as_str(), Some("string"));
+    }
+
+    #[test]
+    fn test_nullable_profile_wraps_ref_in_all_of_instead_of_merging_siblings() {
+        let schema_json =
+            get_type_schema_with_profile::<StructWithOptionalRef>(SchemaProfile::Nullable)
+                .unwrap();
+        let schema_value: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let maybe_info_schema = &schema_value["properties"]["maybe_info"];
+        assert_eq!(maybe_info_schema["nullable"].as_bool(), Some(true));
+        assert!(
+            maybe_info_schema.get("$ref").is_none(),
+            "Expected no bare $ref with a sibling 'nullable' keyword"
+        );
+
+        let all_of = maybe_info_schema["allOf"].as_array().unwrap();
+        assert_eq!(all_of.len(), 1);
+        assert_eq!(
+            all_of[0]["$ref"].as_str(),
+            Some("#/definitions/SimpleStruct")
+        );
+    }
+
+    // Validating responses against a type's Json schema
+    #[test]
+    fn test_validate_response_accepts_conforming_value() {
+        let value = serde_json::json!({"id": 1, "name": "test"});
+
+        assert!(validate_response::<SimpleStruct>(&value).is_ok());
+    }
+
+    #[test]
+    fn test_validate_response_reports_missing_required_field() {
+        let value = serde_json::json!({"id": 1});
+
+        let errors = validate_response::<SimpleStruct>(&value).unwrap_err();
+
+        assert!(!errors.is_empty(), "Expected at least one schema error");
+        assert!(
+            errors.iter().any(|error| error.keyword == "required"),
+            "Expected a 'required' violation, got: {errors:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_response_reports_wrong_type() {
+        let value = serde_json::json!({"id": "not-a-number", "name": "test"});
+
+        let errors = validate_response::<SimpleStruct>(&value).unwrap_err();
+
+        assert!(
+            errors.iter().any(|error| error.path == "/id"),
+            "Expected the violation to be reported at '/id', got: {errors:?}"
+        );
+    }
+
+    // Parsing a response with validation-driven repair retries
+    #[test]
+    fn test_parse_validated_response_succeeds_on_first_try() {
+        let result: SimpleStruct =
+            parse_validated_response("{\"id\": 1, \"name\": \"test\"}", 3, false, |_, _| {
+                panic!("on_invalid should not be called for a conforming response")
+            })
+            .unwrap();
+
+        assert_eq!(result.id, 1);
+        assert_eq!(result.name, "test");
+    }
+
+    #[test]
+    fn test_parse_validated_response_retries_until_valid() {
+        let mut attempts = 0;
+
+        let result: SimpleStruct = parse_validated_response(
+            "{\"id\": \"not-a-number\"}",
+            3,
+            false,
+            |_, errors| {
+                attempts += 1;
+                assert!(!errors.is_empty());
+                Ok("{\"id\": 1, \"name\": \"test\"}".to_string())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(attempts, 1);
+        assert_eq!(result.id, 1);
+    }
+
+    #[test]
+    fn test_parse_validated_response_gives_up_after_max_retries() {
+        let mut attempts = 0;
+
+        let result: Result<SimpleStruct, _> = parse_validated_response(
+            "{\"id\": \"not-a-number\"}",
+            2,
+            false,
+            |_, _| {
+                attempts += 1;
+                Ok("{\"id\": \"still-not-a-number\"}".to_string())
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn test_parse_validated_response_fills_defaults_before_validating() {
+        // `name` is missing entirely, but the schema declares a default for it, so with
+        // `fill_defaults` set this should succeed without ever calling `on_invalid`.
+        let result: StructWithDefault =
+            parse_validated_response("{\"id\": 1}", 3, true, |_, errors| {
+                panic!("on_invalid should not be called when defaults fill the gap: {errors:?}")
+            })
+            .unwrap();
+
+        assert_eq!(result.id, 1);
+        assert_eq!(result.name, "anonymous");
+    }
+
+    #[test]
+    fn test_parse_validated_response_without_fill_defaults_still_fails() {
+        let mut attempts = 0;
+
+        let result: Result<StructWithDefault, _> =
+            parse_validated_response("{\"id\": 1}", 0, false, |_, _| {
+                attempts += 1;
+                Ok("{\"id\": 1}".to_string())
+            });
+
+        // `name` is excluded from the schema's `required` (it only has a `#[schemars(default)]`,
+        // no `#[serde(default)]`), so schema validation passes but the final deserialize into
+        // `StructWithDefault` still fails since serde itself requires the field.
+        assert!(result.is_err());
+        assert_eq!(attempts, 0);
+    }
+
+    #[test]
+    fn test_parse_validated_response_retries_on_malformed_json() {
+        let mut attempts = 0;
+
+        let result: SimpleStruct = parse_validated_response(
+            "not even json",
+            3,
+            false,
+            |_, errors| {
+                attempts += 1;
+                assert!(!errors.is_empty());
+                Ok("{\"id\": 1, \"name\": \"test\"}".to_string())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(attempts, 1);
+        assert_eq!(result.id, 1);
+    }
+
+    #[test]
+    fn test_parse_validated_response_retries_on_final_deserialize_failure() {
+        // Schema validation passes (`name` isn't `required`), but deserializing into the Rust
+        // struct still fails since serde has no default for it — this must also burn a retry
+        // instead of escaping the loop unbounded.
+        let mut attempts = 0;
+
+        let result: StructWithDefault =
+            parse_validated_response("{\"id\": 1}", 1, false, |_, errors| {
+                attempts += 1;
+                assert!(!errors.is_empty());
+                Ok("{\"id\": 1, \"name\": \"test\"}".to_string())
+            })
+            .unwrap();
+
+        assert_eq!(attempts, 1);
+        assert_eq!(result.id, 1);
+        assert_eq!(result.name, "test");
+    }
+
+    // Injecting schema defaults for fields the model omitted
+    #[test]
+    fn test_apply_schema_defaults_fills_missing_field() {
+        let schema_json = get_type_schema::<StructWithDefault>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+        let mut response = serde_json::json!({"id": 1});
+
+        apply_schema_defaults(&schema, &mut response);
+
+        assert_eq!(response["name"], "anonymous");
+    }
+
+    #[test]
+    fn test_apply_schema_defaults_does_not_overwrite_present_value() {
+        let schema_json = get_type_schema::<StructWithDefault>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+        let mut response = serde_json::json!({"id": 1, "name": "given"});
+
+        apply_schema_defaults(&schema, &mut response);
+
+        assert_eq!(response["name"], "given");
+    }
+
+    #[test]
+    fn test_apply_schema_defaults_does_not_overwrite_explicit_null() {
+        let schema_json = get_type_schema::<StructWithDefault>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+        let mut response = serde_json::json!({"id": 1, "name": Value::Null});
+
+        apply_schema_defaults(&schema, &mut response);
+
+        assert!(response["name"].is_null());
+    }
+
+    #[test]
+    fn test_apply_schema_defaults_recurses_into_nested_struct() {
+        let schema_json = get_type_schema::<StructWithDefaultHolder>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+        let mut response = serde_json::json!({"info": {"id": 1}});
+
+        apply_schema_defaults(&schema, &mut response);
+
+        assert_eq!(response["info"]["name"], "anonymous");
+    }
+
+    // Flattening schemas into tool/function-calling parameters
+    #[test]
+    fn test_schema2members_flattens_top_level_properties() {
+        let schema_json = get_type_schema::<ToolParams>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let members = schema2members(&schema).unwrap();
+
+        assert_eq!(members.len(), 4);
+        let city = members.iter().find(|m| m.name == "city").unwrap();
+        assert_eq!(city.description.as_deref(), Some("The city to look up"));
+        assert!(city.required);
+    }
+
+    #[test]
+    fn test_schema2members_marks_optional_fields_not_required() {
+        let schema_json = get_type_schema::<ToolParams>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let members = schema2members(&schema).unwrap();
+
+        let unit = members.iter().find(|m| m.name == "unit").unwrap();
+        assert!(!unit.required);
+    }
+
+    #[test]
+    fn test_schema2members_resolves_ref_into_definitions() {
+        let schema_json = get_type_schema::<ToolParams>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let members = schema2members(&schema).unwrap();
+
+        let info = members.iter().find(|m| m.name == "info").unwrap();
+        assert!(
+            info.schema.get("$ref").is_none(),
+            "Expected the $ref to be resolved into the referenced struct's own schema"
+        );
+        let properties = info.schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("id"));
+        assert!(properties.contains_key("name"));
+    }
+
+    #[test]
+    fn test_schema2members_resolves_ref_wrapped_in_all_of() {
+        // A referenced struct field that also carries its own doc comment gets wrapped by
+        // schemars as `{"description": "...", "allOf": [{"$ref": "..."}]}` rather than a bare
+        // `$ref`, since `$ref` can't have metadata siblings per the JSON Schema spec.
+        let schema_json = get_type_schema::<ToolParams>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_json).unwrap();
+
+        let documented_info = schema["properties"]["documented_info"].clone();
+        assert!(
+            documented_info.get("$ref").is_none(),
+            "Expected schemars to wrap a documented $ref in allOf, not emit a bare $ref"
+        );
+
+        let members = schema2members(&schema).unwrap();
+        let documented_info = members
+            .iter()
+            .find(|m| m.name == "documented_info")
+            .unwrap();
+
+        assert!(
+            documented_info.schema.get("$ref").is_none(),
+            "Expected the allOf-wrapped $ref to be resolved into the referenced struct's own schema"
+        );
+        let properties = documented_info.schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("id"));
+        assert!(properties.contains_key("name"));
+    }
+
+    #[test]
+    fn test_schema2members_errors_on_non_flat_schema() {
+        let schema = serde_json::json!({"type": "string"});
+
+        assert!(schema2members(&schema).is_err());
+    }
+
     // Mapping % target to temperature range
     #[test]
     fn test_target_at_min() {